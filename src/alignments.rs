@@ -1,43 +1,46 @@
-use super::download::{DownloadInfo, Downloadable, MultiDownload};
-use lazy_static::lazy_static;
+use super::assembly::Assembly;
+use super::bai::{self, Chunk};
+use super::bgzf;
+use super::download::{self, Checked, DownloadInfo, Downloadable, MultiDownload, Verbose};
+use anyhow::{Result, anyhow};
+use reqwest::Client;
 
-const SERVER: &str = "https://ftp.ncbi.nlm.nih.gov/genomes/refseq/vertebrate_mammalian/Homo_sapiens/reference/GCF_000001405.40_GRCh38.p14/";
-const MD5FILE: &str = "md5checksums.txt";
-const KNOWNFILE: &str = "GCF_000001405.40_GRCh38.p14_knownrefseq_alns.bam";
-const MODELFILE: &str = "GCF_000001405.40_GRCh38.p14_modelrefseq_alns.bam";
-const LOCALPATH: &str = "downloads/";
+/// Safety margin (in bytes) added past a chunk's end coffset when turning it into an
+/// HTTP byte range, since a BGZF block's on-disk size isn't known until it's fetched.
+const BGZF_MAX_BLOCK_SIZE: u64 = 65536;
 
-lazy_static! {
-    pub static ref ALIGNMENTS: Alns = Alns::new(
-        BamFile::new(
-            KNOWNFILE.to_string(),
-            SERVER.to_string() + "RefSeq_transcripts_alignments/",
-            LOCALPATH.to_string(),
-            AlnType::Known
-        ),
-        BamFile::new(
-            MODELFILE.to_string(),
-            SERVER.to_string() + "RefSeq_transcripts_alignments/",
-            LOCALPATH.to_string(),
-            AlnType::Model
-        )
-    );
-}
+const LOCALPATH: &str = "downloads/";
 
+/// A known/model alignment pool for a single `Assembly`.
 pub struct Alns {
     known: BamFile,
     model: BamFile,
+    server_base: String,
+    md5file: String,
 }
 impl Alns {
-    fn new(known: BamFile, model: BamFile) -> Alns {
+    pub fn new(assembly: &Assembly) -> Alns {
+        let server = assembly.server_base.clone() + &assembly.files.subdir;
         Alns {
-            known: known,
-            model: model,
+            known: BamFile::new(
+                assembly.files.known.clone(),
+                server.clone(),
+                LOCALPATH.to_string(),
+                AlnType::Known,
+            ),
+            model: BamFile::new(
+                assembly.files.model.clone(),
+                server,
+                LOCALPATH.to_string(),
+                AlnType::Model,
+            ),
+            server_base: assembly.server_base.clone(),
+            md5file: assembly.files.md5file.clone(),
         }
     }
 }
 impl MultiDownload for Alns {
-    fn download_pool(&self) -> Option<Vec<impl Downloadable>> {
+    fn download_pool(&self) -> Option<Vec<impl Downloadable + Checked>> {
         Some(vec![
             AlnFileType::BAM(&self.known),
             AlnFileType::BAM(&self.model),
@@ -45,6 +48,9 @@ impl MultiDownload for Alns {
             AlnFileType::BAI(&self.model.bai),
         ])
     }
+    fn checksum_manifest(&self) -> Option<(String, String)> {
+        Some((self.server_base.clone(), self.md5file.clone()))
+    }
 }
 
 struct BaiFile {
@@ -71,6 +77,190 @@ impl BamFile {
             bai: bai_file,
         }
     }
+
+    /// Fetches only the BGZF blocks overlapping `[start, end)` on `ref_name`, using the
+    /// companion `.bai` index and HTTP `Range` requests, instead of the whole BAM. The
+    /// returned bytes are a valid sliced BGZF/BAM stream: header, overlapping record
+    /// blocks, then the EOF marker.
+    pub async fn download_region(&self, ref_name: &str, start: u32, end: u32) -> Result<Vec<u8>> {
+        let client = download::build_client(&download::ClientConfig::default())?;
+        if matches!(self.bai.is_local(), Some(download::LocalFile::None) | None) {
+            download::download(&self.bai, &client, Verbose::Quiet).await?;
+        }
+        let bai_path = match self.bai.localfile() {
+            Some(path) => path,
+            _ => return Err(anyhow!("Failed to get local .bai path")),
+        };
+        let index = bai::parse_bai(&bai_path)?;
+
+        let serverpath = match self.serverfile() {
+            Some(path) => path,
+            _ => return Err(anyhow!("Failed to get server path")),
+        };
+        let (header, ref_names) = fetch_bam_header(&client, &serverpath).await?;
+        let ref_id = match ref_names.iter().position(|name| name == ref_name) {
+            Some(id) => id,
+            _ => return Err(anyhow!("Unknown reference sequence {}", ref_name)),
+        };
+        let ref_index = match index.refs.get(ref_id) {
+            Some(ref_index) => ref_index,
+            _ => return Err(anyhow!("No index entries for reference {}", ref_name)),
+        };
+
+        let min_offset = ref_index
+            .intervals
+            .get((start >> 14) as usize)
+            .copied()
+            .unwrap_or(0);
+        let mut chunks: Vec<Chunk> = bai::reg2bins(start, end)
+            .into_iter()
+            .filter_map(|bin| ref_index.bins.get(&bin))
+            .flatten()
+            .filter(|chunk| chunk.chunk_end > min_offset)
+            .copied()
+            .collect();
+        chunks.sort_by_key(|chunk| chunk.chunk_beg);
+
+        let ranges: Vec<(u64, u64)> = chunks
+            .iter()
+            .map(|chunk| {
+                let beg = bgzf::voffset_to_coffset(chunk.chunk_beg);
+                let end = bgzf::voffset_to_coffset(chunk.chunk_end) + BGZF_MAX_BLOCK_SIZE;
+                (beg, end)
+            })
+            .collect();
+
+        let mut body = Vec::new();
+        for (beg, end) in merge_ranges(ranges) {
+            let raw = fetch_range(&client, &serverpath, beg, end).await?;
+            body.extend_from_slice(truncate_to_complete_blocks(&raw));
+        }
+
+        let mut out = Vec::with_capacity(header.len() + body.len() + bgzf::EOF_MARKER.len());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&body);
+        out.extend_from_slice(&bgzf::EOF_MARKER);
+        Ok(out)
+    }
+}
+
+/// Merges overlapping or adjacent `[beg, end)` byte ranges so the region fetch issues
+/// one HTTP request per contiguous span instead of one per chunk.
+fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_by_key(|r| r.0);
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (beg, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if beg <= *last_end => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => merged.push((beg, end)),
+        }
+    }
+    merged
+}
+
+/// Truncates a fetched span to the end of its last complete BGZF block, dropping the
+/// trailing partial block caused by padding a chunk's end coffset with
+/// `BGZF_MAX_BLOCK_SIZE`. Without this, concatenating spans would splice a partial
+/// block in front of the next span's block-aligned start, corrupting the BGZF stream.
+fn truncate_to_complete_blocks(bytes: &[u8]) -> &[u8] {
+    let mut offset = 0usize;
+    while offset < bytes.len() {
+        match bgzf::block_size(&bytes[offset..]) {
+            Ok(size) if offset + size <= bytes.len() => offset += size,
+            _ => break,
+        }
+    }
+    &bytes[..offset]
+}
+
+/// Fetches the half-open byte range `[beg, end)` of `url` via an HTTP `Range` request.
+async fn fetch_range(client: &Client, url: &str, beg: u64, end: u64) -> Result<Vec<u8>> {
+    let response = client
+        .get(url)
+        .header("Range", format!("bytes={}-{}", beg, end - 1))
+        .send()
+        .await?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Fetches the leading compressed bytes of a BAM file that contain its BGZF header
+/// blocks, decompresses them, and parses the reference sequence names in BAM reference-id
+/// order. Grows the fetch window until a complete header is captured, and returns the
+/// parse error if the header is still incomplete at a 16 MiB window.
+async fn fetch_bam_header(client: &Client, serverpath: &str) -> Result<(Vec<u8>, Vec<String>)> {
+    let mut limit: u64 = 256 * 1024;
+    let max_limit: u64 = 16 * 1024 * 1024;
+    loop {
+        let raw = fetch_range(client, serverpath, 0, limit).await?;
+        let mut offset = 0usize;
+        let mut decompressed = Vec::new();
+        // (compressed offset, cumulative uncompressed length) after each block, so we
+        // can truncate the returned header to a block boundary once we know how many
+        // uncompressed bytes the header actually spans.
+        let mut boundaries: Vec<(usize, usize)> = Vec::new();
+        while offset < raw.len() {
+            let block = &raw[offset..];
+            let size = match bgzf::block_size(block) {
+                Ok(size) if offset + size <= raw.len() => size,
+                _ => break,
+            };
+            decompressed.extend_from_slice(&bgzf::inflate_block(&raw[offset..offset + size])?);
+            offset += size;
+            boundaries.push((offset, decompressed.len()));
+        }
+        match parse_bam_ref_names(&decompressed) {
+            Ok((consumed, ref_names)) => {
+                let header_end = boundaries
+                    .iter()
+                    .find(|(_, uncompressed_len)| *uncompressed_len >= consumed)
+                    .map(|(compressed_offset, _)| *compressed_offset)
+                    .unwrap_or(offset);
+                return Ok((raw[..header_end].to_vec(), ref_names));
+            }
+            Err(_) if limit < max_limit => {
+                limit *= 4;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Parses the binary BAM header (magic, SAM header text, then the reference sequence
+/// dictionary) and returns how many bytes of `data` it consumed along with the
+/// reference names in reference-id order.
+fn parse_bam_ref_names(data: &[u8]) -> Result<(usize, Vec<String>)> {
+    let mut pos = 0usize;
+    if read_slice(data, &mut pos, 4)? != b"BAM\x01" {
+        return Err(anyhow!("Not a BAM header (bad magic)"));
+    }
+    let l_text = read_i32(data, &mut pos)? as usize;
+    pos += l_text;
+    let n_ref = read_i32(data, &mut pos)? as usize;
+    let mut names = Vec::with_capacity(n_ref);
+    for _ in 0..n_ref {
+        let l_name = read_i32(data, &mut pos)? as usize;
+        let name_bytes = read_slice(data, &mut pos, l_name)?;
+        names.push(
+            String::from_utf8_lossy(&name_bytes[..l_name.saturating_sub(1)]).into_owned(),
+        );
+        read_slice(data, &mut pos, 4)?; // l_ref
+    }
+    Ok((pos, names))
+}
+
+fn read_slice<'a>(data: &'a [u8], pos: &mut usize, n: usize) -> Result<&'a [u8]> {
+    if *pos + n > data.len() {
+        return Err(anyhow!("Unexpected end of BAM header data"));
+    }
+    let out = &data[*pos..*pos + n];
+    *pos += n;
+    Ok(out)
+}
+fn read_i32(data: &[u8], pos: &mut usize) -> Result<i32> {
+    let bytes = read_slice(data, pos, 4)?;
+    Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
 }
 
 impl Downloadable for BamFile {
@@ -78,11 +268,13 @@ impl Downloadable for BamFile {
         Some(&self.dlinfo)
     }
 }
+impl Checked for BamFile {}
 impl Downloadable for BaiFile {
     fn download_info(&self) -> Option<&DownloadInfo> {
         Some(&self.dlinfo)
     }
 }
+impl Checked for BaiFile {}
 enum AlnFileType<'a> {
     BAI(&'a BaiFile),
     BAM(&'a BamFile),
@@ -95,7 +287,73 @@ impl<'a> Downloadable for AlnFileType<'a> {
         }
     }
 }
+impl<'a> Checked for AlnFileType<'a> {}
 enum AlnType {
     Known,
     Model,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_ranges_joins_overlapping_and_adjacent_spans() {
+        assert_eq!(
+            merge_ranges(vec![(0, 10), (10, 20), (30, 40)]),
+            vec![(0, 20), (30, 40)]
+        );
+        assert_eq!(merge_ranges(vec![(5, 15), (0, 10)]), vec![(0, 15)]);
+    }
+
+    #[test]
+    fn merge_ranges_keeps_disjoint_spans_separate() {
+        assert_eq!(merge_ranges(vec![(0, 5), (100, 105)]), vec![(0, 5), (100, 105)]);
+    }
+
+    #[test]
+    fn merge_ranges_handles_empty_input() {
+        assert_eq!(merge_ranges(vec![]), Vec::<(u64, u64)>::new());
+    }
+
+    #[test]
+    fn parse_bam_ref_names_reads_the_reference_dictionary() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"BAM\x01");
+        data.extend_from_slice(&0i32.to_le_bytes()); // l_text
+        data.extend_from_slice(&2i32.to_le_bytes()); // n_ref
+        for name in ["chr1", "chr2"] {
+            let mut name_bytes = name.as_bytes().to_vec();
+            name_bytes.push(0);
+            data.extend_from_slice(&(name_bytes.len() as i32).to_le_bytes());
+            data.extend_from_slice(&name_bytes);
+            data.extend_from_slice(&1000i32.to_le_bytes()); // l_ref
+        }
+
+        let (consumed, names) = parse_bam_ref_names(&data).unwrap();
+        assert_eq!(names, vec!["chr1".to_string(), "chr2".to_string()]);
+        assert_eq!(consumed, data.len());
+    }
+
+    #[test]
+    fn parse_bam_ref_names_rejects_bad_magic() {
+        assert!(parse_bam_ref_names(b"NOT!").is_err());
+    }
+
+    #[test]
+    fn truncate_to_complete_blocks_drops_a_trailing_partial_block() {
+        let mut data = bgzf::EOF_MARKER.to_vec();
+        data.extend_from_slice(&bgzf::EOF_MARKER[..bgzf::EOF_MARKER.len() - 1]);
+        assert_eq!(
+            truncate_to_complete_blocks(&data),
+            &bgzf::EOF_MARKER[..]
+        );
+    }
+
+    #[test]
+    fn truncate_to_complete_blocks_keeps_multiple_complete_blocks() {
+        let mut data = bgzf::EOF_MARKER.to_vec();
+        data.extend_from_slice(&bgzf::EOF_MARKER);
+        assert_eq!(truncate_to_complete_blocks(&data), &data[..]);
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+/// The server-relative file names that make up an alignment pool for an assembly.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssemblyFiles {
+    pub subdir: String,
+    pub known: String,
+    pub model: String,
+    pub md5file: String,
+}
+
+/// A RefSeq assembly biodbtools knows how to download alignments for: where it lives
+/// on the NCBI FTP server and which files make up its alignment pool.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Assembly {
+    pub accession: String,
+    pub organism: String,
+    pub server_base: String,
+    pub files: AssemblyFiles,
+}
+
+/// A set of assemblies keyed by short name (e.g. `"GRCh38.p14"`), loadable from a TOML
+/// or JSON config so users can add or override assemblies without recompiling.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct AssemblyRegistry {
+    #[serde(default)]
+    assemblies: HashMap<String, Assembly>,
+}
+
+impl AssemblyRegistry {
+    /// The assemblies biodbtools ships with out of the box.
+    pub fn builtin() -> AssemblyRegistry {
+        let mut assemblies = HashMap::new();
+        assemblies.insert(
+            "GRCh38.p14".to_string(),
+            Assembly {
+                accession: "GCF_000001405.40_GRCh38.p14".to_string(),
+                organism: "Homo sapiens".to_string(),
+                server_base: "https://ftp.ncbi.nlm.nih.gov/genomes/refseq/vertebrate_mammalian/Homo_sapiens/reference/GCF_000001405.40_GRCh38.p14/".to_string(),
+                files: AssemblyFiles {
+                    subdir: "RefSeq_transcripts_alignments/".to_string(),
+                    known: "GCF_000001405.40_GRCh38.p14_knownrefseq_alns.bam".to_string(),
+                    model: "GCF_000001405.40_GRCh38.p14_modelrefseq_alns.bam".to_string(),
+                    md5file: "md5checksums.txt".to_string(),
+                },
+            },
+        );
+        assemblies.insert(
+            "GRCm39".to_string(),
+            Assembly {
+                accession: "GCF_000001635.27_GRCm39".to_string(),
+                organism: "Mus musculus".to_string(),
+                server_base: "https://ftp.ncbi.nlm.nih.gov/genomes/refseq/vertebrate_mammalian/Mus_musculus/reference/GCF_000001635.27_GRCm39/".to_string(),
+                files: AssemblyFiles {
+                    subdir: "RefSeq_transcripts_alignments/".to_string(),
+                    known: "GCF_000001635.27_GRCm39_knownrefseq_alns.bam".to_string(),
+                    model: "GCF_000001635.27_GRCm39_modelrefseq_alns.bam".to_string(),
+                    md5file: "md5checksums.txt".to_string(),
+                },
+            },
+        );
+        AssemblyRegistry { assemblies }
+    }
+
+    /// Loads a registry from a TOML or JSON config file (picked by extension) and fills
+    /// in any assembly the config doesn't mention from the built-ins.
+    pub fn load(path: &str) -> Result<AssemblyRegistry> {
+        let text = fs::read_to_string(path)?;
+        let mut registry: AssemblyRegistry = match path.rsplit('.').next() {
+            Some("json") => serde_json::from_str(&text)?,
+            _ => toml::from_str(&text)?,
+        };
+        for (name, assembly) in AssemblyRegistry::builtin().assemblies {
+            registry.assemblies.entry(name).or_insert(assembly);
+        }
+        Ok(registry)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Assembly> {
+        self.assemblies.get(name)
+    }
+}
+
+lazy_static! {
+    /// The registry used when a caller doesn't load its own config.
+    pub static ref DEFAULT_REGISTRY: AssemblyRegistry = AssemblyRegistry::builtin();
+}
+
+/// Looks up `name` in the default (built-in) registry.
+pub fn default_assembly(name: &str) -> Result<&'static Assembly> {
+    DEFAULT_REGISTRY
+        .get(name)
+        .ok_or_else(|| anyhow!("Unknown assembly {}", name))
+}
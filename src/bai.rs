@@ -0,0 +1,157 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::fs::read;
+
+/// A chunk of alignment records as a pair of BGZF virtual offsets, as stored under a
+/// bin in a `.bai` index.
+#[derive(Clone, Copy)]
+pub struct Chunk {
+    pub chunk_beg: u64,
+    pub chunk_end: u64,
+}
+
+/// The parsed index for a single reference sequence: its bins (UCSC binning scheme)
+/// and the 16kbp linear index used to prune chunks that can't overlap a query.
+pub struct RefIndex {
+    pub bins: HashMap<u32, Vec<Chunk>>,
+    pub intervals: Vec<u64>,
+}
+
+/// A parsed `.bai` index, one `RefIndex` per reference sequence in BAM reference-id order.
+pub struct BaiIndex {
+    pub refs: Vec<RefIndex>,
+}
+
+const MAGIC: &[u8; 4] = b"BAI\x01";
+
+/// Parses a `.bai` file from disk per the SAM spec binary layout.
+pub fn parse_bai(path: &str) -> Result<BaiIndex> {
+    let data = read(path)?;
+    let mut pos = 0usize;
+    let magic = read_bytes(&data, &mut pos, 4)?;
+    if magic != MAGIC {
+        return Err(anyhow!("Not a BAI file (bad magic)"));
+    }
+    let n_ref = read_i32(&data, &mut pos)? as usize;
+    let mut refs = Vec::with_capacity(n_ref);
+    for _ in 0..n_ref {
+        let n_bin = read_i32(&data, &mut pos)? as usize;
+        let mut bins = HashMap::with_capacity(n_bin);
+        for _ in 0..n_bin {
+            let bin = read_u32(&data, &mut pos)?;
+            let n_chunk = read_i32(&data, &mut pos)? as usize;
+            let mut chunks = Vec::with_capacity(n_chunk);
+            for _ in 0..n_chunk {
+                let chunk_beg = read_u64(&data, &mut pos)?;
+                let chunk_end = read_u64(&data, &mut pos)?;
+                chunks.push(Chunk {
+                    chunk_beg,
+                    chunk_end,
+                });
+            }
+            bins.insert(bin, chunks);
+        }
+        let n_intv = read_i32(&data, &mut pos)? as usize;
+        let mut intervals = Vec::with_capacity(n_intv);
+        for _ in 0..n_intv {
+            intervals.push(read_u64(&data, &mut pos)?);
+        }
+        refs.push(RefIndex { bins, intervals });
+    }
+    Ok(BaiIndex { refs })
+}
+
+/// Returns the UCSC-scheme bin ids that can contain records overlapping `[beg, end)`,
+/// per the SAM spec `reg2bins` reference algorithm (levels at offsets 1, 9, 73, 585, 4681).
+/// A zero-length region (`end == 0`, or `end <= beg`) overlaps nothing and returns no bins.
+pub fn reg2bins(beg: u32, end: u32) -> Vec<u32> {
+    if end == 0 || end <= beg {
+        return Vec::new();
+    }
+    let end = end - 1;
+    let mut bins = vec![0u32];
+    for k in (1 + (beg >> 26))..=(1 + (end >> 26)) {
+        bins.push(k);
+    }
+    for k in (9 + (beg >> 23))..=(9 + (end >> 23)) {
+        bins.push(k);
+    }
+    for k in (73 + (beg >> 20))..=(73 + (end >> 20)) {
+        bins.push(k);
+    }
+    for k in (585 + (beg >> 17))..=(585 + (end >> 17)) {
+        bins.push(k);
+    }
+    for k in (4681 + (beg >> 14))..=(4681 + (end >> 14)) {
+        bins.push(k);
+    }
+    bins
+}
+
+fn read_bytes<'a>(data: &'a [u8], pos: &mut usize, n: usize) -> Result<&'a [u8]> {
+    if *pos + n > data.len() {
+        return Err(anyhow!("Unexpected end of BAI data"));
+    }
+    let out = &data[*pos..*pos + n];
+    *pos += n;
+    Ok(out)
+}
+fn read_i32(data: &[u8], pos: &mut usize) -> Result<i32> {
+    let bytes = read_bytes(data, pos, 4)?;
+    Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+fn read_u32(data: &[u8], pos: &mut usize) -> Result<u32> {
+    let bytes = read_bytes(data, pos, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+fn read_u64(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let bytes = read_bytes(data, pos, 8)?;
+    Ok(u64::from_le_bytes([
+        bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reg2bins_small_region_stays_in_top_level_bins() {
+        // beg=0, end=100 never crosses a level boundary, so every level contributes
+        // only its lowest bin plus the implicit bin 0.
+        assert_eq!(reg2bins(0, 100), vec![0, 1, 9, 73, 585, 4681]);
+    }
+
+    #[test]
+    fn reg2bins_empty_region_returns_no_bins() {
+        assert_eq!(reg2bins(100, 0), Vec::<u32>::new());
+        assert_eq!(reg2bins(100, 100), Vec::<u32>::new());
+        assert_eq!(reg2bins(0, 0), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn parse_bai_round_trips_a_minimal_index() {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&1i32.to_le_bytes()); // n_ref
+        data.extend_from_slice(&1i32.to_le_bytes()); // n_bin
+        data.extend_from_slice(&4681u32.to_le_bytes()); // bin id
+        data.extend_from_slice(&1i32.to_le_bytes()); // n_chunk
+        data.extend_from_slice(&0u64.to_le_bytes()); // chunk_beg
+        data.extend_from_slice(&((1u64 << 16) | 0).to_le_bytes()); // chunk_end
+        data.extend_from_slice(&1i32.to_le_bytes()); // n_intv
+        data.extend_from_slice(&0u64.to_le_bytes()); // ioffset
+
+        let path = std::env::temp_dir().join(format!("biodbtools_test_{}.bai", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let index = parse_bai(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(index.refs.len(), 1);
+        assert_eq!(index.refs[0].intervals, vec![0]);
+        let chunks = index.refs[0].bins.get(&4681).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].chunk_beg, 0);
+        assert_eq!(chunks[0].chunk_end, 1 << 16);
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::{Result, anyhow};
+use flate2::read::GzDecoder;
+use std::io::Read;
+
+/// The canonical 28-byte BGZF end-of-file marker (an empty BGZF block). Every valid
+/// BGZF/BAM file ends with one; we append the constant rather than range-fetching it
+/// since its bytes never vary.
+pub const EOF_MARKER: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02,
+    0x00, 0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Reads the `BC` extra-field subfield from a BGZF block header and returns the total
+/// on-disk size of the block (header + deflate payload + CRC/ISIZE trailer).
+pub fn block_size(bytes: &[u8]) -> Result<usize> {
+    if bytes.len() < 18 || bytes[0] != 0x1f || bytes[1] != 0x8b {
+        return Err(anyhow!("Not a gzip/BGZF block"));
+    }
+    let xlen = u16::from_le_bytes([bytes[10], bytes[11]]) as usize;
+    let extra = &bytes[12..12 + xlen];
+    let mut i = 0;
+    while i + 4 <= extra.len() {
+        let si1 = extra[i];
+        let si2 = extra[i + 1];
+        let slen = u16::from_le_bytes([extra[i + 2], extra[i + 3]]) as usize;
+        if si1 == b'B' && si2 == b'C' && slen == 2 {
+            let bsize = u16::from_le_bytes([extra[i + 4], extra[i + 5]]) as usize;
+            return Ok(bsize + 1);
+        }
+        i += 4 + slen;
+    }
+    Err(anyhow!("BGZF block missing BC subfield"))
+}
+
+/// Decompresses a single, complete BGZF block (a standalone gzip member).
+pub fn inflate_block(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = GzDecoder::new(bytes);
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Returns the BGZF compressed-block offset (the high 48 bits) of a virtual file
+/// offset, dropping the within-block uncompressed offset (the low 16 bits).
+pub fn voffset_to_coffset(voffset: u64) -> u64 {
+    voffset >> 16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_size_reads_the_eof_marker() {
+        assert_eq!(block_size(&EOF_MARKER).unwrap(), EOF_MARKER.len());
+    }
+
+    #[test]
+    fn inflate_block_decompresses_the_eof_marker_to_nothing() {
+        assert_eq!(inflate_block(&EOF_MARKER).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn block_size_rejects_non_gzip_bytes() {
+        assert!(block_size(&[0u8; 20]).is_err());
+    }
+
+    #[test]
+    fn voffset_to_coffset_keeps_only_the_high_48_bits() {
+        assert_eq!(voffset_to_coffset(0), 0);
+        assert_eq!(voffset_to_coffset((42u64 << 16) | 0xffff), 42);
+    }
+}
@@ -1,21 +1,96 @@
 use anyhow::{Result, anyhow};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use md5::Context;
 use reqwest::Client;
-use std::fs::remove_file;
+use std::collections::HashMap;
+use std::fs::{File as StdFile, remove_file};
+use std::io::Read;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
-use tokio::fs::File;
+use std::time::Duration;
+use reqwest::StatusCode;
+use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Semaphore;
+
+const MD5_CHUNK_SIZE: usize = 64 * 1024;
+const DEFAULT_CONCURRENCY: usize = 3;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_USER_AGENT: &str = concat!("biodbtools/", env!("CARGO_PKG_VERSION"));
+
+/// Tunables for the shared `Client` built by `build_client`. `read_timeout` is generous
+/// by default since it bounds the whole transfer, not just a single chunk read, and
+/// this crate targets multi-gigabyte BAMs.
+pub struct ClientConfig {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub user_agent: String,
+}
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(60 * 60),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+        }
+    }
+}
+
+/// Builds the `Client` that should be shared across all `download`/`check` calls for a
+/// run, so connections and TLS sessions are pooled instead of rebuilt per file.
+pub fn build_client(config: &ClientConfig) -> Result<Client> {
+    Ok(Client::builder()
+        .connect_timeout(config.connect_timeout)
+        .timeout(config.read_timeout)
+        .user_agent(config.user_agent.clone())
+        .build()?)
+}
 
 pub trait MultiDownload {
-    fn download_pool(&self) -> Option<Vec<impl Downloadable>>;
+    fn download_pool(&self) -> Option<Vec<impl Downloadable + Checked>>;
+    /// The `(server_root, manifest_filename)` to fetch the MD5 manifest from, e.g. the
+    /// assembly root rather than a particular file's own (sub)directory.
+    fn checksum_manifest(&self) -> Option<(String, String)> {
+        None
+    }
+}
+
+/// Tunables for `download_all`: how many transfers run at once, and how many times a
+/// failed transfer is retried with exponential backoff before giving up.
+#[derive(Clone, Copy)]
+pub struct DownloadConfig {
+    pub concurrency: usize,
+    pub max_retries: u32,
 }
-pub async fn download_all<M: MultiDownload>(pool: &M, verbose: Verbose) -> Result<()> {
+impl Default for DownloadConfig {
+    fn default() -> DownloadConfig {
+        DownloadConfig {
+            concurrency: DEFAULT_CONCURRENCY,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+}
+
+pub async fn download_all<M: MultiDownload>(
+    pool: &M,
+    verbose: Verbose,
+    config: DownloadConfig,
+) -> Result<()> {
     let mut handles = vec![];
     let dls = match pool.download_pool() {
         Some(dl) => dl,
         _ => return Err(anyhow!("No download pool")),
     };
+    let client = build_client(&ClientConfig::default())?;
+    // Fetched once up front and shared by every task below, rather than re-downloaded
+    // per file: the manifest is the same for the whole pool.
+    let manifest = match pool.checksum_manifest() {
+        Some((manifest_server, manifest_file)) => Some(Arc::new(
+            fetch_md5_manifest(&client, &manifest_server, &manifest_file).await?,
+        )),
+        _ => None,
+    };
+    let semaphore = Arc::new(Semaphore::new(config.concurrency));
     let mp = match verbose {
         Verbose::Int(m) => Arc::new(Mutex::new(m)),
         _ => Arc::new(Mutex::new(MultiProgress::new())),
@@ -23,20 +98,59 @@ pub async fn download_all<M: MultiDownload>(pool: &M, verbose: Verbose) -> Resul
     for dl in dls {
         let inner_mp = Arc::clone(&mp);
         let inner_verbose = verbose.clone();
+        let inner_client = client.clone();
+        let inner_manifest = manifest.clone();
+        let inner_semaphore = Arc::clone(&semaphore);
         handles.push(tokio::task::spawn(async move {
+            let _permit = inner_semaphore.acquire().await?;
             let pb = inner_mp.lock().unwrap().add(ProgressBar::new(0));
             let ipb = match inner_verbose {
                 Verbose::Quiet => Verbose::Quiet,
                 _ => Verbose::Ext(pb),
             };
-            download(&dl, Verbose::Quiet);
+            download_with_retry(&dl, &inner_client, ipb, config.max_retries).await?;
+            if let Some(manifest) = inner_manifest {
+                check(&dl, &manifest).await?;
+            }
+            Ok::<(), anyhow::Error>(())
         }));
     }
-    futures::future::join_all(handles).await;
-    mp.lock();
+    let results = futures::future::join_all(handles).await;
+    let errors: Vec<String> = results
+        .into_iter()
+        .filter_map(|r| match r {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(e) => Some(e.to_string()),
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(anyhow!("download_all failed: {}", errors.join("; ")));
+    }
     Ok(())
 }
 
+/// Retries `download` up to `max_retries` times with exponential backoff (1s, 2s, 4s, ...)
+/// before giving up, so a transient network hiccup doesn't fail the whole pool.
+async fn download_with_retry<D: Downloadable>(
+    dl: &D,
+    client: &Client,
+    verbose: Verbose,
+    max_retries: u32,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match download(dl, client, verbose.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(_e) if attempt < max_retries => {
+                attempt += 1;
+                tokio::time::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 pub trait Downloadable {
     fn download_info(&self) -> Option<&DownloadInfo>;
     fn pb_style(&self) -> ProgressStyle {
@@ -71,8 +185,12 @@ pub trait Downloadable {
     }
 }
 
-pub async fn download<D: Downloadable>(dl: &D, verbose: Verbose) -> Result<()> {
-    let client = Client::new();
+/// Downloads `dl` to `<localfile>.partial`, resuming from wherever that file left off via
+/// an HTTP `Range` request, and atomically renames it to the final `localfile()` on
+/// completion. Small metadata files (e.g. the MD5 manifest) are fetched directly with
+/// the client rather than going through this resumable path. `client` is shared across
+/// calls so connection pooling and TLS session reuse aren't thrown away per file.
+pub async fn download<D: Downloadable>(dl: &D, client: &Client, verbose: Verbose) -> Result<()> {
     let serverpath = match dl.serverfile() {
         Some(serverpath) => serverpath,
         _ => return Err(anyhow!("Failed to get server path")),
@@ -81,13 +199,37 @@ pub async fn download<D: Downloadable>(dl: &D, verbose: Verbose) -> Result<()> {
         Some(localfilepath) => localfilepath,
         _ => return Err(anyhow!("Failed to get local path")),
     };
-    let mut response = client.get(serverpath).send().await?;
+    let partialpath = localfilepath.clone() + ".partial";
+    let resume_from = match tokio::fs::metadata(&partialpath).await {
+        Ok(meta) => meta.len(),
+        _ => 0,
+    };
+    let request = match resume_from {
+        0 => client.get(&serverpath),
+        n => client
+            .get(&serverpath)
+            .header("Range", format!("bytes={}-", n)),
+    };
+    let mut response = request.send().await?;
+    if resume_from > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // A prior run wrote the whole transfer to `.partial` but died before the final
+        // rename, so the server has nothing left past `resume_from`. Treat the partial
+        // file as finished rather than truncating it and restarting from the 416 body.
+        tokio::fs::rename(&partialpath, &localfilepath).await?;
+        if let Verbose::Ext(pb) = verbose {
+            pb.finish();
+        }
+        return Ok(());
+    }
+    let resuming = resume_from > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
     let download_size = match response.content_length() {
+        Some(ds) if resuming => ds + resume_from,
         Some(ds) => ds,
         _ => 0,
     };
     let pb = match verbose {
         Verbose::Ext(pb) => {
+            pb.set_length(download_size);
             pb.set_style(dl.pb_style());
             Some(pb)
         }
@@ -98,8 +240,16 @@ pub async fn download<D: Downloadable>(dl: &D, verbose: Verbose) -> Result<()> {
         }
         _ => None,
     };
-    let file = File::create(localfilepath).await?;
+    let file = if resuming {
+        OpenOptions::new().append(true).open(&partialpath).await?
+    } else {
+        File::create(&partialpath).await?
+    };
     let mut writer = BufWriter::new(file);
+    match pb.as_ref() {
+        Some(p) if resuming => p.inc(resume_from),
+        _ => (),
+    };
     while let Some(chunk) = response.chunk().await? {
         writer.write(&chunk).await?;
         match pb.as_ref() {
@@ -108,14 +258,89 @@ pub async fn download<D: Downloadable>(dl: &D, verbose: Verbose) -> Result<()> {
         };
     }
     writer.flush().await?;
+    tokio::fs::rename(&partialpath, &localfilepath).await?;
     match pb.as_ref() {
         Some(p) => p.finish(),
         _ => (),
     };
     Ok(())
 }
-pub trait Checked: Downloadable {
-    fn check(&self) -> Result<()>;
+pub trait Checked: Downloadable {}
+
+/// Verifies `dl`'s local file against an already-fetched MD5 manifest (see
+/// `fetch_md5_manifest`), which callers downloading a whole pool should fetch once
+/// and share rather than re-fetching per file.
+pub async fn check<D: Checked>(dl: &D, manifest: &HashMap<String, String>) -> Result<Checksum> {
+    let serverpath = match dl.serverfile() {
+        Some(serverpath) => serverpath,
+        _ => return Err(anyhow!("Failed to get server path")),
+    };
+    let basename = serverpath.rsplit('/').next().unwrap_or(&serverpath);
+    let localfile = match dl.localfile() {
+        Some(localfile) => localfile,
+        _ => return Err(anyhow!("Failed to get local path")),
+    };
+    let expected = match manifest.get(basename) {
+        Some(expected) => expected,
+        _ => return Err(anyhow!("No checksum entry for {}", basename)),
+    };
+    let actual = compute_md5_file(&localfile)?;
+    if &actual != expected {
+        return Err(anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            basename,
+            expected,
+            actual
+        ));
+    }
+    Ok(Checksum::Hash(actual))
+}
+
+/// Fetches `manifest_file` (e.g. `md5checksums.txt`) from `server` and parses it into a
+/// map of `./path/to/filename` entries keyed by their basename, to their 32-char
+/// lowercase MD5 hash.
+async fn fetch_md5_manifest(
+    client: &Client,
+    server: &str,
+    manifest_file: &str,
+) -> Result<HashMap<String, String>> {
+    let text = client
+        .get(server.to_string() + manifest_file)
+        .send()
+        .await?
+        .text()
+        .await?;
+    let mut manifest = HashMap::new();
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        let hash = match parts.next() {
+            Some(hash) => hash,
+            _ => continue,
+        };
+        let path = match parts.next() {
+            Some(path) => path,
+            _ => continue,
+        };
+        let basename = path.rsplit('/').next().unwrap_or(path);
+        manifest.insert(basename.to_string(), hash.to_string());
+    }
+    Ok(manifest)
+}
+
+/// Streams `path` through an MD5 hasher in fixed-size chunks so memory stays flat
+/// even for multi-gigabyte BAMs.
+fn compute_md5_file(path: &str) -> Result<String> {
+    let mut file = StdFile::open(path)?;
+    let mut ctx = Context::new();
+    let mut buf = [0u8; MD5_CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        ctx.consume(&buf[..n]);
+    }
+    Ok(format!("{:x}", ctx.compute()))
 }
 
 #[derive(Clone)]
@@ -170,10 +395,17 @@ pub enum LocalFile {
     Exists,
     None,
 }
+/// Controls how much progress reporting a `download`/`download_all` call does.
 #[derive(Clone)]
-enum Verbose {
+pub enum Verbose {
+    /// No progress reporting.
     Quiet,
+    /// Render a standalone progress bar for this transfer.
     Loud,
+    /// Render progress onto a bar the caller already registered (e.g. with a
+    /// `MultiProgress`), as `download_all` does for each pool entry.
     Ext(ProgressBar),
+    /// Render progress onto a `MultiProgress` the caller owns: `download_all` adds one
+    /// bar per transfer as it starts. A single `download` call reports no progress.
     Int(MultiProgress),
 }